@@ -5,14 +5,44 @@ use std::{
     str::from_utf8,
 };
 
+// The splice and mmap fast paths below need a raw file descriptor, which is a unix-only concept.
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use memmap2::Mmap;
+
 use structopt::StructOpt;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 use rayon::prelude::*;
 use itertools::Itertools;
 
 // The line characters to use when counting maximum line length.
 const LINE_CHARS: &[char] = &['\n', '\r', '\u{0C}'];
 
+// Columns a tab advances to, i.e. it jumps to the next multiple of this width.
+const TAB_WIDTH: usize = 8;
+
+// Size of the buffer used by the raw-byte fast path in `count_file`.
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+// Regular files at least this big get split across threads instead of scanned sequentially.
+const PARALLEL_SCAN_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+// Lets `count_file` stay generic over its reader even though the splice and mmap fast paths
+// (unix-only, since they need a raw file descriptor) require `AsRawFd`. On unix this is just
+// `AsRawFd`; everywhere else it's trivially satisfied by everything, and those fast paths are
+// compiled out instead.
+#[cfg(unix)]
+trait MaybeRawFd: AsRawFd {}
+#[cfg(unix)]
+impl<T: AsRawFd> MaybeRawFd for T {}
+
+#[cfg(not(unix))]
+trait MaybeRawFd {}
+#[cfg(not(unix))]
+impl<T> MaybeRawFd for T {}
+
 #[derive(StructOpt)]
 /// Print newline, word, and byte counts for each FILE, and a total line if more than one FILE is
 /// specified.  A word is a non-zero-length sequence of characters delimited by white space.
@@ -107,10 +137,209 @@ impl Counts {
     }
 }
 
-fn count_file<R: Read>(args: &Args, file: R, file_path: Option<&str>) -> Result<Counts, io::Error> {
+// Computes the display width of a line the way a terminal would render it: a tab advances the
+// column to the next multiple of `TAB_WIDTH`, wide/fullwidth characters take two columns,
+// zero-width and combining marks take none, and everything else takes one. The column only
+// ever moves forward, so the final column is also the max column reached.
+fn display_width(line: &str) -> usize {
+    let mut col = 0;
+
+    for c in line.chars() {
+        if c == '\t' {
+            col = (col / TAB_WIDTH + 1) * TAB_WIDTH;
+        } else {
+            col += UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+    }
+
+    col
+}
+
+// Counts words directly on raw bytes instead of requiring a validated `str`, so a line that
+// isn't valid UTF-8 still gets a sensible word count instead of failing outright. Only treats
+// ASCII whitespace as a separator; non-ASCII whitespace code points are (rarely) counted as
+// part of a word, which is the same trade-off GNU wc's C locale makes.
+fn count_words(bytes: &[u8]) -> usize {
+    let mut words = 0;
+    let mut prev_was_space = true;
+
+    for &byte in bytes {
+        let is_space = byte.is_ascii_whitespace();
+        if prev_was_space && !is_space {
+            words += 1;
+        }
+        prev_was_space = is_space;
+    }
+
+    words
+}
+
+// Intermediate per-chunk result used when a single large file is split across threads. Bytes
+// and lines are plain totals for the chunk (a newline always falls inside exactly one chunk, so
+// summing never double-counts), but a word straddling either edge can't be resolved until the
+// chunk is stitched to its neighbour, so the leading/trailing edges are tracked separately.
+//
+// This intentionally doesn't track display width: `-L` needs tab stops and wide/combining
+// character widths resolved against a whole decoded line, which (like char counting) isn't safe
+// to reconstruct from raw byte chunks split at arbitrary boundaries, so `-L` always takes the
+// sequential path in `count_file` instead.
+#[derive(Clone, Copy, Default)]
+struct ChunkCounts {
+    bytes: u64,
+    lines: usize,
+    words: usize,
+    starts_nonspace: bool,
+    ends_nonspace: bool,
+}
+
+// Scans one chunk in isolation, treating the byte just before it as whitespace for the purpose
+// of word counting; `combine_chunks` corrects for a word straddling the chunk boundary.
+fn count_chunk(chunk: &[u8]) -> ChunkCounts {
+    let mut words = 0;
+    let mut prev_was_space = true;
+
+    for &byte in chunk {
+        let is_space = byte.is_ascii_whitespace();
+        if prev_was_space && !is_space {
+            words += 1;
+        }
+        prev_was_space = is_space;
+    }
+
+    ChunkCounts {
+        bytes: chunk.len() as u64,
+        lines: bytecount::count(chunk, b'\n'),
+        words,
+        starts_nonspace: chunk.first().is_some_and(|b| !b.is_ascii_whitespace()),
+        ends_nonspace: chunk.last().is_some_and(|b| !b.is_ascii_whitespace()),
+    }
+}
+
+// Merges two chunk results that are adjacent in the original file, with `a` preceding `b`.
+// Must be applied strictly in file order: the boundary correction below assumes `a`'s trailing
+// edge is actually next to `b`'s leading edge.
+fn combine_chunks(a: ChunkCounts, b: ChunkCounts) -> ChunkCounts {
+    let straddling_word = a.ends_nonspace && b.starts_nonspace;
+
+    ChunkCounts {
+        bytes: a.bytes + b.bytes,
+        lines: a.lines + b.lines,
+        words: a.words + b.words - straddling_word as usize,
+        starts_nonspace: a.starts_nonspace,
+        ends_nonspace: b.ends_nonspace,
+    }
+}
+
+// Folds a sequence of per-chunk results into one, in file order. Seeds the fold with the first
+// real chunk rather than `ChunkCounts::default()`: the default's `starts_nonspace` is `false`,
+// which would silently become the merged result's leading edge once more than one chunk is
+// folded in, rather than whatever the data's actual first byte is.
+fn reduce_chunks(chunks: impl Iterator<Item = ChunkCounts>) -> ChunkCounts {
+    chunks.reduce(combine_chunks).unwrap_or_default()
+}
+
+// Splits `data` into roughly-equal chunks, counts each in parallel via rayon, then stitches the
+// per-chunk results back together in order so a word straddling a chunk boundary is counted
+// exactly once.
+fn count_bytes_parallel(data: &[u8]) -> Counts {
+    let num_chunks = rayon::current_num_threads().max(1);
+    let chunk_len = (data.len() / num_chunks).max(1);
+
+    let merged = reduce_chunks(data.par_chunks(chunk_len).map(count_chunk).collect::<Vec<_>>().into_iter());
+
+    Counts {
+        words: merged.words,
+        lines: merged.lines,
+        bytes: merged.bytes,
+        ..Counts::default()
+    }
+}
+
+// Number of reused buffers kept in flight between the reader thread and the counting loop.
+const STREAM_BUFFER_COUNT: usize = 4;
+
+// Pipelines reads and counting for streaming inputs that can't be mmapped or split by byte
+// range (stdin, pipes): a producer thread keeps filling buffers while the loop below chews
+// through the previous one, so the kernel is filling the next buffer while we're still
+// counting the last. Reuses `count_chunk`/`combine_chunks` from the single-file parallel path
+// to stitch words across buffer boundaries, so the line, word and byte counts are identical to
+// counting the whole stream sequentially. Line length isn't tracked here, so `-L` (like `-m`)
+// never takes this path.
+fn count_stream_overlapped<R: Read + Send + 'static>(mut reader: R) -> io::Result<ChunkCounts> {
+    use std::sync::mpsc::sync_channel;
+
+    let (filled_tx, filled_rx) = sync_channel::<(Vec<u8>, usize)>(STREAM_BUFFER_COUNT);
+    let (empty_tx, empty_rx) = sync_channel::<Vec<u8>>(STREAM_BUFFER_COUNT);
+
+    for _ in 0..STREAM_BUFFER_COUNT {
+        empty_tx.send(vec![0u8; READ_BUF_SIZE]).expect("channel was just created");
+    }
+
+    let reader_thread = std::thread::spawn(move || -> io::Result<()> {
+        while let Ok(mut buf) = empty_rx.recv() {
+            let n = reader.read(&mut buf)?;
+            if n == 0 || filled_tx.send((buf, n)).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    });
+
+    // `None` until the first buffer arrives, same as `reduce_chunks`: seeding this with
+    // `ChunkCounts::default()` would make the merged result's `starts_nonspace` always `false`
+    // instead of the stream's real first byte.
+    let mut merged: Option<ChunkCounts> = None;
+    for (buf, n) in filled_rx.iter() {
+        let chunk = count_chunk(&buf[..n]);
+        merged = Some(match merged {
+            Some(prev) => combine_chunks(prev, chunk),
+            None => chunk,
+        });
+        // Hand the buffer back so the reader thread can reuse it instead of allocating anew.
+        let _ = empty_tx.send(buf);
+    }
+
+    reader_thread.join().expect("reader thread panicked")?;
+
+    Ok(merged.unwrap_or_default())
+}
+
+// Uses splice(2) to move bytes directly from `in_fd` to /dev/null inside the kernel, without
+// ever copying them into userspace. Only works when `in_fd` refers to a pipe (splice requires
+// at least one end of the pair to be a pipe), so regular files and terminals fail immediately
+// with EINVAL and we fall back to the normal read loop. Returns the bytes counted so far and
+// whether the input was fully drained, so a mid-stream EINVAL/ENOSYS doesn't lose any bytes.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn splice_count_bytes(in_fd: i32) -> io::Result<(u64, bool)> {
+    let dev_null = std::fs::OpenOptions::new().write(true).open("/dev/null")?;
+    let out_fd = dev_null.as_raw_fd();
+
+    let mut bytes = 0u64;
+    loop {
+        let n = unsafe {
+            libc::splice(in_fd, std::ptr::null_mut(), out_fd, std::ptr::null_mut(), READ_BUF_SIZE, 0)
+        };
+
+        if n < 0 {
+            return match io::Error::last_os_error().raw_os_error() {
+                Some(libc::EINVAL) | Some(libc::ENOSYS) => Ok((bytes, false)),
+                _ => Err(io::Error::last_os_error()),
+            };
+        }
+
+        if n == 0 {
+            return Ok((bytes, true));
+        }
+
+        bytes += n as u64;
+    }
+}
+
+fn count_file<R: Read + MaybeRawFd + Send + 'static>(args: &Args, file: R, file_path: Option<&str>) -> Result<Counts, io::Error> {
     let mut buffer = BufReader::new(file);
 
-    let mut line_buf = String::new();
+    let mut line_buf: Vec<u8> = Vec::new();
     let mut counts = Counts::default();
 
     // If we need the byte length and this is a file, we can just query the file system.
@@ -123,39 +352,124 @@ fn count_file<R: Read>(args: &Args, file: R, file_path: Option<&str>) -> Result<
         _ => {}
     }
 
+    // Large regular files can be mapped into memory and split across threads instead of
+    // streamed line-by-line. Character counting and display-width line length both need to
+    // track UTF-8 continuation bytes (and, for width, combining marks) across chunk boundaries,
+    // which isn't worth the complexity here, so those still take the sequential path below.
+    // mmap-ing needs a raw file descriptor, so this is unix-only, same as the splice path below.
+    #[cfg(unix)]
+    if let Some(file_path) = file_path {
+        if args.needs_read() && !args.count_chars && !args.max_line_length {
+            let meta = Path::new(file_path).metadata()?;
+            if meta.len() >= PARALLEL_SCAN_THRESHOLD {
+                // Safety: we only read the mapping; wc doesn't expect the file to be mutated
+                // by another process while it's running.
+                // Mapping can fail for non-seekable files (e.g. a FIFO opened by path), in
+                // which case we fall through to the normal streaming path below.
+                if let Ok(mmap) = unsafe { Mmap::map(buffer.get_ref()) } {
+                    return Ok(count_bytes_parallel(&mmap));
+                }
+            }
+        }
+    }
+
     // Input might be from stdin, so we may need to read the stream even if it's just byte count.
     if args.needs_read() || file_path.is_none() {
-        while buffer.read_line(&mut line_buf)? > 0 {
-            counts.lines += 1;
+        // Byte-only counts from stdin don't need the data itself, just its length, so when it's
+        // a pipe or FIFO let the kernel shuffle it straight into /dev/null instead of copying it
+        // to us. Any other kind of input makes splice fail and takes the read loop below.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if file_path.is_none() && args.count_bytes && !args.needs_read() {
+                let in_fd = buffer.get_ref().as_raw_fd();
+                let (spliced, drained) = splice_count_bytes(in_fd)?;
+                counts.bytes += spliced;
+
+                if drained {
+                    return Ok(counts);
+                }
+                // Unsupported fd combination (e.g. a regular file): fall through to the normal
+                // read loop below for whatever's left, continuing to add to `counts.bytes`.
+            }
+        }
+
+        // Words, chars and max line length all need a per-line or per-byte scan, so if none of
+        // those were asked for we can skip the line-by-line loop entirely and just count
+        // newlines and bytes straight out of a reused byte buffer, letting SIMD do the work.
+        if !args.count_words && !args.count_chars && !args.max_line_length {
+            let mut buf = vec![0u8; READ_BUF_SIZE];
+
+            loop {
+                let n = buffer.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+
+                counts.lines += bytecount::count(&buf[..n], b'\n');
+
+                // If this isn't a file, we need to count the bytes in here.
+                if file_path.is_none() && args.count_bytes {
+                    counts.bytes += n as u64;
+                }
+            }
+
+            return Ok(counts);
+        }
+
+        // Words are needed from a stream we can't mmap or range-split, so pipeline the reads on
+        // a separate thread instead of looping read_until one line at a time. Character counting
+        // and display-width line length still take the sequential path below, same as the mmap
+        // path, since both need properly decoded text across buffer boundaries.
+        if file_path.is_none() && !args.count_chars && !args.max_line_length {
+            let reader = buffer.into_inner();
+            let merged = count_stream_overlapped(reader)?;
+
+            counts.lines = merged.lines;
+            counts.words = merged.words;
+
+            if args.count_bytes {
+                counts.bytes = merged.bytes;
+            }
+
+            return Ok(counts);
+        }
+
+        // read_until operates on raw bytes, so a file that isn't valid UTF-8 (binary data, or
+        // just a stray invalid sequence) no longer aborts the whole count, matching GNU wc.
+        while buffer.read_until(b'\n', &mut line_buf)? > 0 {
+            // A final read with no trailing newline is a partial line, not a counted one, same
+            // as the bytecount/chunk-based paths above.
+            if line_buf.ends_with(b"\n") {
+                counts.lines += 1;
+            }
 
             // If this isn't a file, we need to count the bytes in here.
             if file_path.is_none() && args.count_bytes {
-                counts.bytes += line_buf.as_bytes().len() as u64;
+                counts.bytes += line_buf.len() as u64;
             }
 
             // These are the two expensive ones, so put them behind a flag.
             if args.count_words {
-                counts.words += line_buf.split_whitespace().count();
+                counts.words += count_words(&line_buf);
             }
 
             if args.count_chars || args.max_line_length {
-                let count = match args.utf_chars {
-                    true  => line_buf.graphemes(true).count(),
-                    false => line_buf.chars().count(),
-                };
-
-                counts.chars += count;
-                let line_len = match line_buf.ends_with(LINE_CHARS) { // 0xC is form feed.
-                    true  => {
-                        // line break is a single-byte character, so we can just find the difference
-                        // between the byte lengths of the pre-trimmed and the trimmed version.
-                        let diff = line_buf.as_bytes().len() - line_buf.trim_end_matches(LINE_CHARS).as_bytes().len();
-                        count - diff
-                    },
-                    false => count,
-                };
-
-                counts.max_line_len = counts.max_line_len.max(line_len);
+                // Invalid sequences are replaced rather than bailing out, so grapheme/codepoint
+                // counting still works on the rest of a line that isn't entirely valid UTF-8.
+                let line_str = String::from_utf8_lossy(&line_buf);
+
+                if args.count_chars {
+                    counts.chars += match args.utf_chars {
+                        true  => line_str.graphemes(true).count(),
+                        false => line_str.chars().count(),
+                    };
+                }
+
+                if args.max_line_length {
+                    // 0xC is form feed; already excluded from the width, same as the line break.
+                    let line = line_str.trim_end_matches(LINE_CHARS);
+                    counts.max_line_len = counts.max_line_len.max(display_width(line));
+                }
             }
 
             line_buf.clear();
@@ -269,3 +583,158 @@ fn main() -> MyResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Splits `data` at `at` and merges the two halves exactly the way `count_bytes_parallel`
+    // merges adjacent chunks, so these tests exercise the real boundary-merge path.
+    fn merge_at(data: &[u8], at: usize) -> ChunkCounts {
+        combine_chunks(count_chunk(&data[..at]), count_chunk(&data[at..]))
+    }
+
+    #[test]
+    fn straddling_word_is_counted_once() {
+        // Split mid-word: "foo b" | "ar" -- "bar" straddles the boundary.
+        let merged = merge_at(b"foo bar", 5);
+        assert_eq!(merged.words, 2);
+    }
+
+    #[test]
+    fn straddling_line_is_still_counted_once() {
+        let data = "short\nthis is a longer line\n";
+        let nl_pos = data.bytes().position(|b| b == b'\n').unwrap();
+        let at = nl_pos + 1 + 5; // split partway through the second line
+
+        let merged = merge_at(data.as_bytes(), at);
+
+        assert_eq!(merged.lines, 2);
+    }
+
+    #[test]
+    fn chunk_with_no_newline_has_no_lines() {
+        let chunk = count_chunk(b"no newlines here");
+
+        assert_eq!(chunk.lines, 0);
+        assert_eq!(chunk.words, 3);
+    }
+
+    #[test]
+    fn empty_chunk_merges_as_identity() {
+        // Identity for the line and word totals only: the merged leading edge is the empty
+        // chunk's `false`, which is why `reduce_chunks` seeds with a real chunk.
+        let merged = combine_chunks(ChunkCounts::default(), count_chunk(b"hello\nworld\n"));
+
+        assert_eq!(merged.lines, 2);
+        assert_eq!(merged.words, 2);
+    }
+
+    #[test]
+    fn bytecount_counts_newlines_the_same_way_a_manual_scan_would() {
+        // Pins the exact semantics the SIMD fast path in count_file relies on: a plain count of
+        // `\n` occurrences, not e.g. "number of lines" (which would differ on a trailing
+        // newline) or any other off-by-one interpretation.
+        let data = b"a\nbb\nccc\n\nddd";
+        assert_eq!(bytecount::count(data, b'\n'), data.iter().filter(|&&b| b == b'\n').count());
+        assert_eq!(bytecount::count(data, b'\n'), 4);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn splice_count_bytes_drains_a_real_pipe() {
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0, "failed to create pipe");
+        let [read_fd, write_fd] = fds;
+
+        let payload = b"hello from a real pipe\n";
+        // Owns write_fd; dropping it closes the write end so splice sees EOF instead of blocking.
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        writer.write_all(payload).unwrap();
+        drop(writer);
+
+        // Owns read_fd so it gets closed once the test is done with it.
+        let reader = unsafe { File::from_raw_fd(read_fd) };
+        let (bytes, drained) = splice_count_bytes(reader.as_raw_fd()).unwrap();
+
+        assert_eq!(bytes, payload.len() as u64);
+        assert!(drained);
+    }
+
+    #[test]
+    fn count_stream_overlapped_matches_a_sequential_scan() {
+        let data = b"one two\nthree four five\nsix".to_vec();
+
+        let merged = count_stream_overlapped(io::Cursor::new(data.clone())).unwrap();
+
+        assert_eq!(merged.lines, 2);
+        assert_eq!(merged.words, 6);
+        assert_eq!(merged.bytes, data.len() as u64);
+    }
+
+    #[test]
+    fn count_stream_overlapped_handles_an_empty_reader() {
+        let merged = count_stream_overlapped(io::Cursor::new(Vec::new())).unwrap();
+
+        assert_eq!(merged.lines, 0);
+        assert_eq!(merged.words, 0);
+        assert_eq!(merged.bytes, 0);
+    }
+
+    #[test]
+    fn count_words_splits_on_ascii_whitespace() {
+        assert_eq!(count_words(b"  foo   bar\tbaz\n"), 3);
+    }
+
+    #[test]
+    fn count_words_is_zero_for_all_whitespace() {
+        assert_eq!(count_words(b"   \t\n  "), 0);
+    }
+
+    #[test]
+    fn count_words_handles_invalid_utf8_without_panicking() {
+        // A lone continuation byte (0x80) isn't valid UTF-8 on its own, but count_words works on
+        // raw bytes, so it's just two non-whitespace runs either side of the ASCII space.
+        assert_eq!(count_words(b"ab \x80cd"), 2);
+    }
+
+    #[test]
+    fn display_width_counts_plain_ascii_one_column_each() {
+        assert_eq!(display_width("hello"), 5);
+    }
+
+    #[test]
+    fn display_width_advances_tabs_to_the_next_stop() {
+        // 'a' takes column 0 to 1, then the tab jumps to the next multiple of TAB_WIDTH (8).
+        assert_eq!(display_width("a\t"), 8);
+        // Already sitting on a tab stop: the tab still advances a full stop, not zero columns.
+        assert_eq!(display_width("\t\t"), 16);
+    }
+
+    #[test]
+    fn display_width_counts_wide_characters_as_two_columns() {
+        assert_eq!(display_width("\u{4e2d}"), 2); // CJK ideograph 中
+    }
+
+    #[test]
+    fn display_width_counts_combining_marks_as_zero_columns() {
+        // 'e' followed by a combining acute accent (U+0301): one column, not two.
+        assert_eq!(display_width("e\u{0301}"), 1);
+    }
+
+    #[test]
+    fn reduce_seeds_with_first_real_chunk_not_default() {
+        // Three chunks of one word each, split on whitespace so no word straddles a boundary:
+        // "bar " then "baz " then "qux". Folding with `.fold(ChunkCounts::default(), combine_chunks)`
+        // would still get the word count right here (the bug doesn't corrupt it), but it would
+        // leave `starts_nonspace` as the default's `false` instead of the data's real leading
+        // edge (`true`) -- `reduce_chunks` must seed with the first chunk to get this right.
+        let chunks = vec![count_chunk(b"bar "), count_chunk(b"baz "), count_chunk(b"qux")];
+        let merged = reduce_chunks(chunks.into_iter());
+
+        assert!(merged.starts_nonspace);
+        assert_eq!(merged.words, 3);
+    }
+}